@@ -0,0 +1,4 @@
+//! Backend server for the sshx terminal-sharing service.
+
+pub mod session;
+pub mod web;
@@ -0,0 +1,654 @@
+//! Server-side session state that isn't carried by the wire protocol alone.
+//!
+//! The [`web::protocol`](crate::web::protocol) module defines what goes
+//! *over the wire*; [`SessionState`] is what the server keeps *in memory*
+//! for a single room to make sense of it — who's connected, how recently
+//! they've been heard from, chat history, saved layouts, and in-progress
+//! file transfers.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sshx_core::{Sid, Uid};
+use tokio::sync::Mutex;
+
+use crate::web::protocol::{
+    assign_color, thread_order, ChatMessage, WsLayout, WsServer, WsUser, WsWinsize,
+};
+
+/// How often the server checks for stale connections and reclaims them.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a client may go without a heartbeat before the server
+/// considers it gone and reclaims its slot.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a disconnected user's identity and resume secret are kept
+/// around, so a client that reconnects can resume it instead of rejoining
+/// as a brand-new participant.
+pub const RESUME_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// How long a user may go without a substantive action (cursor move,
+/// keystroke, chat) before the server marks them idle.
+pub const IDLE_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Maximum number of bytes buffered server-side for one shell's in-flight
+/// transfer. Since transfers are held fully in memory until `FileEnd`, this
+/// is also the hard cap on the size a client may declare in
+/// `WsClient::FileBegin`: accepting a larger declared size there would just
+/// guarantee a mid-transfer abort once it's exceeded.
+pub const MAX_FILE_BUFFER: u64 = 8 << 20; // 8 MiB
+
+/// A disconnected user kept around during the resume grace period.
+struct PendingResume {
+    user: WsUser,
+    secret: String,
+    disconnected_at: Instant,
+}
+
+/// An in-progress chunked file transfer into a shell's input.
+struct FileTransfer {
+    name: String,
+    declared_size: u64,
+    received: Vec<u8>,
+}
+
+/// Shared, mutable state for a single collaborative session.
+#[derive(Default)]
+pub struct SessionState {
+    /// Currently connected users.
+    users: HashMap<Uid, WsUser>,
+    /// Last time a heartbeat or other message was seen from each user.
+    last_seen: HashMap<Uid, Instant>,
+    /// Per-user resume secret, issued at `Hello` and valid until the user
+    /// is fully forgotten (including through the resume grace period).
+    secrets: HashMap<Uid, String>,
+    /// Users that disconnected recently enough to still be resumable.
+    pending_resumes: HashMap<Uid, PendingResume>,
+    /// Open shells, tracked here (rather than just in the terminal layer)
+    /// so their layout can be replayed to a resuming or newly-subscribing
+    /// client.
+    shells: Vec<(Sid, WsWinsize)>,
+    /// Chat messages sent in this room, in receipt order.
+    chat: Vec<ChatMessage>,
+    /// Next ID to assign to a new chat message.
+    next_chat_id: u64,
+    /// Named, saved window layouts.
+    layouts: HashMap<String, WsLayout>,
+    /// File transfers in progress, keyed by the shell they target.
+    transfers: HashMap<Sid, FileTransfer>,
+    /// Last time each user took a substantive action, used to compute
+    /// `WsUser::idle`.
+    last_active: HashMap<Uid, Instant>,
+}
+
+impl SessionState {
+    /// Pick the first palette color not already held by a currently
+    /// connected user, so two live users never collide. If every palette
+    /// slot is already taken (more live users than colors), falls back to
+    /// the first slot — a collision is then unavoidable.
+    fn next_free_color(&self) -> (u8, u8, u8) {
+        let taken: std::collections::HashSet<(u8, u8, u8)> =
+            self.users.values().map(|u| u.color).collect();
+        (0..self.users.len() + 1)
+            .map(assign_color)
+            .find(|color| !taken.contains(color))
+            .unwrap_or_else(|| assign_color(0))
+    }
+
+    /// Handle a new client's `Hello`: assign it a color not currently in
+    /// use by another live user (the first palette color once every color
+    /// is taken), start tracking its liveness, and issue a fresh resume
+    /// secret. Returns the `(WsUser, secret)` to send back.
+    pub fn join(&mut self, uid: Uid, name: String) -> (WsUser, String) {
+        let color = self.next_free_color();
+        let user = WsUser {
+            name,
+            cursor: None,
+            focus: None,
+            color,
+            idle: false,
+        };
+        let secret = self.add_user(uid, user.clone());
+        (user, secret)
+    }
+
+    /// Register a newly connected user, start tracking its liveness, and
+    /// issue a fresh resume secret for it.
+    pub fn add_user(&mut self, uid: Uid, user: WsUser) -> String {
+        let secret = generate_secret();
+        self.users.insert(uid, user);
+        self.last_seen.insert(uid, Instant::now());
+        self.last_active.insert(uid, Instant::now());
+        self.secrets.insert(uid, secret.clone());
+        secret
+    }
+
+    /// Override a connected user's color, e.g. from `WsClient::SetColor`.
+    /// Returns `false` if the user isn't connected.
+    pub fn set_color(&mut self, uid: Uid, color: (u8, u8, u8)) -> bool {
+        match self.users.get_mut(&uid) {
+            Some(user) => {
+                user.color = color;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record that `uid` just took a substantive action (cursor move,
+    /// keystroke, chat message), resetting its idle timer. Returns `true`
+    /// if this un-idled the user, so the caller knows to broadcast a
+    /// `UserDiff`.
+    pub fn mark_active(&mut self, uid: Uid) -> bool {
+        self.last_active.insert(uid, Instant::now());
+        match self.users.get_mut(&uid) {
+            Some(user) if user.idle => {
+                user.idle = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Flip any user inactive for longer than [`IDLE_THRESHOLD`] to idle,
+    /// returning the IDs of users whose idle state just changed so the
+    /// caller can broadcast a `UserDiff` for each.
+    pub fn update_idle(&mut self) -> Vec<Uid> {
+        let now = Instant::now();
+        let mut changed = Vec::new();
+        for (&uid, user) in self.users.iter_mut() {
+            let inactive = self
+                .last_active
+                .get(&uid)
+                .map(|&seen| now.duration_since(seen) > IDLE_THRESHOLD)
+                .unwrap_or(false);
+            if inactive && !user.idle {
+                user.idle = true;
+                changed.push(uid);
+            }
+        }
+        changed
+    }
+
+    /// Look up a connected user's current state.
+    pub fn user(&self, uid: Uid) -> Option<&WsUser> {
+        self.users.get(&uid)
+    }
+
+    /// A snapshot of the room's current users and open shells, for
+    /// replaying to a client that just joined or resumed.
+    pub fn replay(&self) -> (Vec<(Uid, WsUser)>, Vec<(Sid, WsWinsize)>) {
+        let users = self
+            .users
+            .iter()
+            .map(|(&uid, user)| (uid, user.clone()))
+            .collect();
+        (users, self.shells.clone())
+    }
+
+    /// Track a newly created shell, in open order.
+    pub fn open_shell(&mut self, sid: Sid, winsize: WsWinsize) {
+        self.shells.push((sid, winsize));
+    }
+
+    /// Stop tracking a closed shell.
+    pub fn close_shell(&mut self, sid: Sid) {
+        self.shells.retain(|&(s, _)| s != sid);
+    }
+
+    /// Update a shell's tracked position and size, e.g. from
+    /// `WsClient::Move`.
+    pub fn move_shell(&mut self, sid: Sid, winsize: WsWinsize) {
+        if let Some(slot) = self.shells.iter_mut().find(|(s, _)| *s == sid) {
+            slot.1 = winsize;
+        }
+    }
+
+    /// Capture the current arrangement of open shells as a named layout,
+    /// for later recall with [`SessionState::apply_layout`].
+    pub fn save_layout(&mut self, name: String) -> WsLayout {
+        let layout = WsLayout {
+            name: name.clone(),
+            slots: self.shells.iter().map(|&(_, winsize)| winsize).collect(),
+        };
+        self.layouts.insert(name, layout.clone());
+        layout
+    }
+
+    /// Reposition the currently open shells to match a previously saved
+    /// layout, by slot order, returning the updated `Shells` snapshot to
+    /// broadcast. Returns `None` if no layout with that name exists.
+    pub fn apply_layout(&mut self, name: &str) -> Option<Vec<(Sid, WsWinsize)>> {
+        let layout = self.layouts.get(name)?;
+        for (slot, &winsize) in self.shells.iter_mut().zip(layout.slots.iter()) {
+            slot.1 = winsize;
+        }
+        Some(self.shells.clone())
+    }
+
+    /// The names of all layouts saved for this session, for
+    /// `WsServer::Layouts`.
+    pub fn layout_names(&self) -> Vec<String> {
+        self.layouts.keys().cloned().collect()
+    }
+
+    /// Begin a chunked file transfer into `sid`'s input, rejecting it
+    /// up front if the declared size exceeds [`MAX_FILE_BUFFER`] — there's
+    /// no point accepting a transfer that's guaranteed to blow the
+    /// buffering limit partway through. Replaces any prior unfinished
+    /// transfer into the same shell.
+    pub fn file_begin(&mut self, sid: Sid, name: String, size: u64) -> Result<(), String> {
+        if size > MAX_FILE_BUFFER {
+            return Err(format!(
+                "file {name:?} of {size} bytes exceeds the {MAX_FILE_BUFFER}-byte limit"
+            ));
+        }
+        self.transfers.insert(
+            sid,
+            FileTransfer {
+                name,
+                declared_size: size,
+                received: Vec::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Append a chunk to an in-progress transfer, enforcing backpressure
+    /// against both the transfer's declared size and [`MAX_FILE_BUFFER`].
+    /// Returns the total bytes received so far, for `WsServer::FileAck`.
+    pub fn file_chunk(&mut self, sid: Sid, data: Vec<u8>) -> Result<u64, String> {
+        let transfer = self
+            .transfers
+            .get_mut(&sid)
+            .ok_or_else(|| format!("no file transfer in progress for shell {sid}"))?;
+
+        let total = transfer.received.len() as u64 + data.len() as u64;
+        if total > transfer.declared_size {
+            self.transfers.remove(&sid);
+            return Err("chunk exceeds the transfer's declared size".to_string());
+        }
+        if total > MAX_FILE_BUFFER {
+            self.transfers.remove(&sid);
+            return Err("transfer exceeds the server's buffering limit".to_string());
+        }
+
+        transfer.received.extend_from_slice(&data);
+        Ok(transfer.received.len() as u64)
+    }
+
+    /// Finish a chunked file transfer, returning the reassembled bytes to
+    /// feed into the shell's input. Returns `None` if there was no
+    /// transfer in progress for `sid`.
+    pub fn file_end(&mut self, sid: Sid) -> Option<Vec<u8>> {
+        self.transfers.remove(&sid).map(|t| t.received)
+    }
+
+    /// Attempt to resume a previously issued identity, given the `Uid` and
+    /// secret from a [`WsClient::Resume`](crate::web::protocol::WsClient::Resume).
+    ///
+    /// Returns the resumed user's state on success. If the user is still
+    /// actively tracked (e.g. a quick reconnect before the heartbeat reaper
+    /// noticed), no `UserDiff` needs to be sent at all. If it was only
+    /// recently disconnected (within [`RESUME_GRACE_PERIOD`]), its identity
+    /// is reinstated and the caller should broadcast a fresh
+    /// `UserDiff(uid, Some(user))` to undo the one sent at disconnect.
+    /// Returns `None` on an invalid or expired secret, in which case the
+    /// caller should fall back to issuing a brand-new `Hello`.
+    pub fn resume(&mut self, uid: Uid, secret: &str) -> Option<WsUser> {
+        if self.secrets.get(&uid).map(String::as_str) == Some(secret) && self.users.contains_key(&uid) {
+            self.touch(uid);
+            return self.users.get(&uid).cloned();
+        }
+
+        let pending = self.pending_resumes.get(&uid)?;
+        if pending.secret != secret || pending.disconnected_at.elapsed() > RESUME_GRACE_PERIOD {
+            return None;
+        }
+        let mut pending = self.pending_resumes.remove(&uid).unwrap();
+        // The user is active again as of this reconnect, regardless of
+        // whatever idle state was captured at disconnect time.
+        pending.user.idle = false;
+        self.users.insert(uid, pending.user.clone());
+        self.last_seen.insert(uid, Instant::now());
+        self.last_active.insert(uid, Instant::now());
+        self.secrets.insert(uid, pending.secret);
+        Some(pending.user)
+    }
+
+    /// Remove expired resumable identities that have outlived the grace
+    /// period, forgetting them for good.
+    pub fn prune_expired_resumes(&mut self) {
+        self.pending_resumes
+            .retain(|_, pending| pending.disconnected_at.elapsed() <= RESUME_GRACE_PERIOD);
+    }
+
+    /// Record a chat message from `uid`, assigning it the next message ID.
+    pub fn hear(&mut self, uid: Uid, name: String, text: String, parent: Option<u64>) -> ChatMessage {
+        let message = ChatMessage {
+            id: self.next_chat_id,
+            uid,
+            name,
+            text,
+            parent,
+        };
+        self.next_chat_id += 1;
+        self.chat.push(message.clone());
+        message
+    }
+
+    /// The full chat history, in depth-first thread order.
+    pub fn chat_thread(&self) -> Vec<ChatMessage> {
+        thread_order(&self.chat)
+    }
+
+    /// Record that a heartbeat (or any other message) was just received
+    /// from `uid`, resetting its liveness timer. No-op for unknown users.
+    pub fn touch(&mut self, uid: Uid) {
+        if let Some(seen) = self.last_seen.get_mut(&uid) {
+            *seen = Instant::now();
+        }
+    }
+
+    /// Handle a client `Ping`, refreshing its liveness and returning the
+    /// `Pong` to send back.
+    pub fn ping(&mut self, uid: Uid, nonce: u64) -> WsServer {
+        self.touch(uid);
+        WsServer::Pong(nonce)
+    }
+
+    /// Drop every user whose last heartbeat is older than
+    /// [`CLIENT_TIMEOUT`], reclaiming their slot, and return their IDs so
+    /// the caller can broadcast a `UserDiff(uid, None)` for each.
+    ///
+    /// The user's identity and secret are kept around for
+    /// [`RESUME_GRACE_PERIOD`] in case the client reconnects and resumes.
+    pub fn reap_stale(&mut self) -> Vec<Uid> {
+        let now = Instant::now();
+        let stale: Vec<Uid> = self
+            .last_seen
+            .iter()
+            .filter(|&(_, &seen)| now.duration_since(seen) > CLIENT_TIMEOUT)
+            .map(|(&uid, _)| uid)
+            .collect();
+        for &uid in &stale {
+            if let Some(user) = self.users.remove(&uid) {
+                self.last_seen.remove(&uid);
+                self.last_active.remove(&uid);
+                if let Some(secret) = self.secrets.remove(&uid) {
+                    self.pending_resumes.insert(
+                        uid,
+                        PendingResume {
+                            user,
+                            secret,
+                            disconnected_at: now,
+                        },
+                    );
+                }
+            }
+        }
+        stale
+    }
+}
+
+/// Generate a random, URL-safe secret token for a
+/// [`WsClient::Resume`](crate::web::protocol::WsClient::Resume) handshake.
+fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Run forever, periodically reaping stale connections and updating idle
+/// state in `state`, invoking `on_diff` for each user whose visible state
+/// changed so the caller can broadcast a `UserDiff`: `None` for a user
+/// reclaimed as stale, or `Some(user)` for one that just went idle.
+///
+/// Intended to be spawned as a background task alongside the WebSocket
+/// accept loop for a session.
+pub async fn run_heartbeat_reaper(
+    state: Arc<Mutex<SessionState>>,
+    on_diff: impl Fn(Uid, Option<WsUser>),
+) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut state = state.lock().await;
+        let stale = state.reap_stale();
+        state.prune_expired_resumes();
+        let idled: Vec<(Uid, WsUser)> = state
+            .update_idle()
+            .into_iter()
+            .filter_map(|uid| state.user(uid).cloned().map(|user| (uid, user)))
+            .collect();
+        drop(state);
+        for uid in stale {
+            on_diff(uid, None);
+        }
+        for (uid, user) in idled {
+            on_diff(uid, Some(user));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    fn user(name: &str) -> WsUser {
+        WsUser {
+            name: name.into(),
+            cursor: None,
+            focus: None,
+            color: (0, 0, 0),
+            idle: false,
+        }
+    }
+
+    #[test]
+    fn reaps_only_stale_users() {
+        let mut state = SessionState::default();
+        let alice: Uid = 1;
+        let bob: Uid = 2;
+        state.add_user(alice, user("alice"));
+        state.add_user(bob, user("bob"));
+
+        // Freshly added users are within the timeout window.
+        assert!(state.reap_stale().is_empty());
+
+        // Keep "alice" alive, but let "bob" go quiet.
+        state.touch(alice);
+        sleep(CLIENT_TIMEOUT + Duration::from_millis(10));
+        state.touch(alice);
+
+        let stale = state.reap_stale();
+        assert_eq!(stale, vec![bob]);
+        assert!(state.user(alice).is_some());
+        assert!(state.user(bob).is_none());
+    }
+
+    #[test]
+    fn resumes_within_grace_period() {
+        let mut state = SessionState::default();
+        let bob: Uid = 2;
+        let secret = state.add_user(bob, user("bob"));
+
+        state.reap_stale(); // not yet stale, no-op
+        sleep(CLIENT_TIMEOUT + Duration::from_millis(10));
+        let stale = state.reap_stale();
+        assert_eq!(stale, vec![bob]);
+        assert!(state.user(bob).is_none());
+
+        // Resuming with the right secret brings the identity back.
+        let resumed = state.resume(bob, &secret);
+        assert_eq!(resumed.map(|u| u.name), Some("bob".to_string()));
+        assert!(state.user(bob).is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_resume_secret() {
+        let mut state = SessionState::default();
+        let bob: Uid = 2;
+        state.add_user(bob, user("bob"));
+        sleep(CLIENT_TIMEOUT + Duration::from_millis(10));
+        state.reap_stale();
+
+        assert!(state.resume(bob, "wrong-secret").is_none());
+    }
+
+    #[test]
+    fn resume_clears_idle_flag_from_before_disconnect() {
+        let mut state = SessionState::default();
+        let bob: Uid = 2;
+        let mut bob_user = user("bob");
+        bob_user.idle = true;
+        let secret = state.add_user(bob, bob_user);
+
+        sleep(CLIENT_TIMEOUT + Duration::from_millis(10));
+        state.reap_stale();
+
+        let resumed = state.resume(bob, &secret).unwrap();
+        assert!(!resumed.idle);
+        assert!(!state.user(bob).unwrap().idle);
+    }
+
+    #[test]
+    fn threads_chat_in_depth_first_order() {
+        let mut state = SessionState::default();
+        let alice: Uid = 1;
+        let a = state.hear(alice, "alice".into(), "root".into(), None);
+        let b = state.hear(alice, "alice".into(), "reply to root".into(), Some(a.id));
+        state.hear(alice, "alice".into(), "sibling reply".into(), Some(a.id));
+        state.hear(alice, "alice".into(), "reply to reply".into(), Some(b.id));
+
+        let ids: Vec<u64> = state.chat_thread().iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![0, 1, 3, 2]);
+    }
+
+    #[test]
+    fn assigns_distinct_colors_to_live_users() {
+        let mut state = SessionState::default();
+        let (alice, _) = state.join(1, "alice".into());
+        let (bob, _) = state.join(2, "bob".into());
+        assert_ne!(alice.color, bob.color);
+    }
+
+    #[test]
+    fn set_color_overrides_assignment() {
+        let mut state = SessionState::default();
+        let (alice, _) = state.join(1, "alice".into());
+        assert!(state.set_color(1, (1, 2, 3)));
+        assert_eq!(state.user(1).unwrap().color, (1, 2, 3));
+        assert_ne!(state.user(1).unwrap().color, alice.color);
+    }
+
+    #[test]
+    fn color_assignment_tracks_live_users_not_cumulative_joins() {
+        let mut state = SessionState::default();
+        // Join and then remove users one at a time, well past the 8-color
+        // palette's worth of cumulative joins, while never having more than
+        // one live user at a time.
+        for uid in 1..=10 {
+            let (user, _) = state.join(uid, format!("user{uid}"));
+            assert_eq!(user.color, assign_color(0), "a lone live user should always get the first palette color, regardless of join count");
+            state.users.remove(&uid);
+        }
+
+        // With many users simultaneously live, colors are assigned by the
+        // first free palette slot rather than colliding.
+        let mut colors = Vec::new();
+        for uid in 100..108 {
+            let (user, _) = state.join(uid, format!("user{uid}"));
+            colors.push(user.color);
+        }
+        let unique: std::collections::HashSet<_> = colors.iter().collect();
+        assert_eq!(unique.len(), colors.len(), "8 simultaneously live users should get 8 distinct colors");
+    }
+
+    #[test]
+    fn marks_users_idle_after_inactivity() {
+        let mut state = SessionState::default();
+        state.join(1, "alice".into());
+        assert!(state.update_idle().is_empty());
+
+        // Backdate the last-active timestamp instead of sleeping for real.
+        state
+            .last_active
+            .insert(1, Instant::now() - IDLE_THRESHOLD - Duration::from_secs(1));
+
+        let changed = state.update_idle();
+        assert_eq!(changed, vec![1]);
+        assert!(state.user(1).unwrap().idle);
+
+        // Activity un-idles the user immediately.
+        assert!(state.mark_active(1));
+        assert!(!state.user(1).unwrap().idle);
+    }
+
+    #[test]
+    fn saves_and_applies_layout_by_slot_order() {
+        let mut state = SessionState::default();
+        state.open_shell(1, WsWinsize { x: 0, y: 0, rows: 24, cols: 80 });
+        state.open_shell(2, WsWinsize { x: 10, y: 10, rows: 24, cols: 80 });
+        state.save_layout("grid".into());
+
+        // Move the shells around, then re-apply the saved layout.
+        state.move_shell(1, WsWinsize { x: 99, y: 99, rows: 24, cols: 80 });
+        let shells = state.apply_layout("grid").unwrap();
+        assert_eq!(shells[0].1.x, 0);
+        assert_eq!(shells[1].1.x, 10);
+
+        assert_eq!(state.layout_names(), vec!["grid".to_string()]);
+        assert!(state.apply_layout("missing").is_none());
+    }
+
+    #[test]
+    fn reassembles_a_chunked_file_transfer() {
+        let mut state = SessionState::default();
+        state.file_begin(1, "a.txt".into(), 6).unwrap();
+        assert_eq!(state.file_chunk(1, b"foo".to_vec()).unwrap(), 3);
+        assert_eq!(state.file_chunk(1, b"bar".to_vec()).unwrap(), 6);
+        assert_eq!(state.file_end(1), Some(b"foobar".to_vec()));
+        // The transfer is gone once finished.
+        assert!(state.file_chunk(1, b"x".to_vec()).is_err());
+    }
+
+    #[test]
+    fn rejects_declared_size_over_the_hard_limit() {
+        let mut state = SessionState::default();
+        assert!(state
+            .file_begin(1, "huge.bin".into(), MAX_FILE_BUFFER + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_declared_size_between_buffer_and_old_64mib_cap_up_front() {
+        // Regression test: a size in the 8-64 MiB range used to pass
+        // file_begin (checked against a 64 MiB cap) only to guarantee an
+        // abort the moment file_chunk's MAX_FILE_BUFFER check tripped.
+        // It must now be rejected immediately instead.
+        let mut state = SessionState::default();
+        let declared_size = MAX_FILE_BUFFER + (32 << 20); // 40 MiB
+        assert!(state
+            .file_begin(1, "medium.bin".into(), declared_size)
+            .is_err());
+        // No transfer should have been started for it.
+        assert!(state.file_chunk(1, b"x".to_vec()).is_err());
+    }
+
+    #[test]
+    fn rejects_chunks_exceeding_the_declared_size() {
+        let mut state = SessionState::default();
+        state.file_begin(1, "a.txt".into(), 2).unwrap();
+        assert!(state.file_chunk(1, b"abc".to_vec()).is_err());
+        // The oversized chunk aborts the transfer.
+        assert!(state.file_chunk(1, b"a".to_vec()).is_err());
+    }
+}
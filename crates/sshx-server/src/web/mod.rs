@@ -0,0 +1,3 @@
+//! HTTP and WebSocket interface to the server.
+
+pub mod protocol;
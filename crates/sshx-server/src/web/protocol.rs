@@ -1,4 +1,9 @@
 //! Serializable types sent and received by the web server.
+//!
+//! Clients send periodic [`WsClient::Ping`] heartbeats, which the server
+//! echoes back as [`WsServer::Pong`]. The server uses the liveness of this
+//! exchange, rather than relying on the TCP layer alone, to detect and
+//! reclaim connections from clients that have silently dropped.
 
 use std::sync::Arc;
 
@@ -54,14 +59,147 @@ pub struct WsUser {
     pub cursor: Option<(i32, i32)>,
     /// Currently focused terminal window ID.
     pub focus: Option<Sid>,
+    /// The user's cursor and name-tag color, as an RGB triple.
+    pub color: (u8, u8, u8),
+    /// Whether the user is currently away, based on how long it has been
+    /// since their last input (heartbeats alone do not keep a user active).
+    pub idle: bool,
+}
+
+/// A fixed palette of visually distinct colors. Each joining user gets the
+/// first one not held by another live user, so no two live users collide
+/// as long as there are no more live users than colors.
+const COLOR_PALETTE: &[(u8, u8, u8)] = &[
+    (230, 25, 75),   // red
+    (60, 180, 75),   // green
+    (255, 225, 25),  // yellow
+    (0, 130, 200),   // blue
+    (245, 130, 48),  // orange
+    (145, 30, 180),  // purple
+    (70, 240, 240),  // cyan
+    (240, 50, 230),  // magenta
+];
+
+/// Return the palette color at `index`, wrapping around past the end of
+/// the palette.
+pub fn assign_color(index: usize) -> (u8, u8, u8) {
+    COLOR_PALETTE[index % COLOR_PALETTE.len()]
+}
+
+/// A named, saved arrangement of shell windows.
+///
+/// Captures the relative [`WsWinsize`] of each open shell, in the order it
+/// was opened, so that it can later be reapplied to reposition whatever
+/// shells happen to be open at the time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct WsLayout {
+    /// The name this layout was saved under.
+    pub name: String,
+    /// Window sizes for each shell slot, in open order.
+    pub slots: Vec<WsWinsize>,
+}
+
+/// A single chat message in a threaded conversation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    /// Server-assigned, monotonically increasing message ID.
+    pub id: u64,
+    /// ID of the user who sent the message.
+    pub uid: Uid,
+    /// The user's display name at the time the message was sent.
+    pub name: String,
+    /// Contents of the chat message.
+    pub text: String,
+    /// ID of the message this one is replying to, if any.
+    pub parent: Option<u64>,
+}
+
+/// Order a set of chat messages into depth-first thread order.
+///
+/// Starts from the roots (messages with no parent, in their original
+/// order) and for each one, immediately recurses into its children before
+/// moving on to the next sibling or root. Any `parent` that doesn't refer
+/// to another message in `messages`, or that points at the message itself,
+/// is treated as a cycle and ignored, leaving that message unreachable
+/// from the roots.
+pub fn thread_order(messages: &[ChatMessage]) -> Vec<ChatMessage> {
+    let mut children: std::collections::HashMap<u64, Vec<&ChatMessage>> =
+        std::collections::HashMap::new();
+    let ids: std::collections::HashSet<u64> = messages.iter().map(|m| m.id).collect();
+    let mut roots = Vec::new();
+    for m in messages {
+        match m.parent {
+            None => roots.push(m),
+            Some(parent) if parent != m.id && ids.contains(&parent) => {
+                children.entry(parent).or_default().push(m);
+            }
+            // Self-referential or otherwise cyclic parent: drop the
+            // message rather than surface it as a spurious root.
+            Some(_) => {}
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(messages.len());
+    let mut stack: Vec<&ChatMessage> = roots.into_iter().rev().collect();
+    while let Some(message) = stack.pop() {
+        if let Some(kids) = children.get(&message.id) {
+            stack.extend(kids.iter().rev());
+        }
+        ordered.push(message.clone());
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod thread_order_tests {
+    use super::*;
+
+    fn msg(id: u64, parent: Option<u64>) -> ChatMessage {
+        ChatMessage {
+            id,
+            uid: 0,
+            name: "u".into(),
+            text: "t".into(),
+            parent,
+        }
+    }
+
+    #[test]
+    fn orders_depth_first_not_breadth_first() {
+        // A has children B, C (B before C); B has child D.
+        // DFS: A, B, D, C. (Level order would wrongly give A, B, C, D.)
+        let a = msg(1, None);
+        let b = msg(2, Some(1));
+        let c = msg(3, Some(1));
+        let d = msg(4, Some(2));
+        let ordered = thread_order(&[a, b, c, d]);
+        let ids: Vec<u64> = ordered.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![1, 2, 4, 3]);
+    }
+
+    #[test]
+    fn ignores_cycles_and_self_parents() {
+        let a = msg(1, None);
+        let self_parent = msg(2, Some(2));
+        let cycle_a = msg(3, Some(4));
+        let cycle_b = msg(4, Some(3));
+        let ordered = thread_order(&[a, self_parent, cycle_a, cycle_b]);
+        // Unreachable messages (self-parent, mutual cycle) are dropped.
+        let ids: Vec<u64> = ordered.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![1]);
+    }
 }
 
 /// A real-time message sent from the server over WebSocket.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum WsServer {
-    /// Initial server message, informing the user of their ID.
-    Hello(Uid),
+    /// Initial server message, informing the user of their ID and a secret
+    /// token that can later be used to [resume](WsClient::Resume) this
+    /// identity after a reconnect.
+    Hello(Uid, String),
     /// A snapshot of all current users in the session.
     Users(Vec<(Uid, WsUser)>),
     /// Info about a single user in the session: joined, left, or changed.
@@ -70,8 +208,15 @@ pub enum WsServer {
     Shells(Vec<(Sid, WsWinsize)>),
     /// Subscription results, in the form of terminal data chunks.
     Chunks(Sid, Vec<Arc<str>>),
-    /// Get a chat message tuple `(uid, name, text)` from the room.
-    Hear(Uid, String, String),
+    /// Get a chat message from the room.
+    Hear(ChatMessage),
+    /// Respond to a client's heartbeat, echoing back its nonce.
+    Pong(u64),
+    /// Advertise the names of layouts saved for this session.
+    Layouts(Vec<String>),
+    /// Acknowledge receipt of a chunk from a [`WsClient::FileChunk`] upload,
+    /// reporting the total number of bytes received so far for the shell.
+    FileAck(Sid, u64),
     /// The current session has been terminated.
     Terminated(),
     /// Alert the client of an application error.
@@ -98,6 +243,44 @@ pub enum WsClient {
     Data(Sid, #[serde(with = "serde_bytes")] Vec<u8>),
     /// Subscribe to a shell, starting at a given chunk index.
     Subscribe(Sid, u64),
-    /// Send a a chat message to the room.
-    Chat(String),
+    /// Send a chat message to the room, optionally as a reply to an
+    /// existing message ID.
+    Chat {
+        /// Contents of the chat message.
+        text: String,
+        /// ID of the message this one is replying to, if any.
+        parent: Option<u64>,
+    },
+    /// Heartbeat sent to the server, carrying an opaque nonce.
+    Ping(u64),
+    /// Override the server-assigned color for the current user.
+    SetColor((u8, u8, u8)),
+    /// Save the current arrangement of open shells under a name, for later
+    /// recall with [`WsClient::ApplyLayout`].
+    SaveLayout(String),
+    /// Reposition the currently open shells to match a previously saved
+    /// layout, by slot order, emitting a fresh [`WsServer::Shells`].
+    ApplyLayout(String),
+    /// Begin a chunked file transfer into a shell's input, naming the file
+    /// and declaring its total size up front so the server can enforce a
+    /// maximum size and apply backpressure.
+    FileBegin { sid: Sid, name: String, size: u64 },
+    /// A chunk of bytes in an in-progress [`WsClient::FileBegin`] transfer.
+    FileChunk {
+        sid: Sid,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    /// Mark the end of a chunked file transfer, after which the server
+    /// reassembles and delivers the bytes to the shell's input.
+    FileEnd { sid: Sid },
+    /// Resume a previous identity after reconnecting, given its user ID and
+    /// the secret token originally issued in [`WsServer::Hello`].
+    ///
+    /// If the token is valid and the user is still tracked (or was
+    /// disconnected recently enough to be within the grace window), the
+    /// server rebinds this connection to that `Uid` instead of issuing a new
+    /// one, replaying the current [`WsServer::Users`] and [`WsServer::Shells`]
+    /// state. An invalid or expired token falls back to a fresh `Hello`.
+    Resume(Uid, String),
 }
\ No newline at end of file